@@ -18,57 +18,94 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::{
+use core::{
     cell::UnsafeCell,
+    marker::PhantomData,
     ops::{Deref, DerefMut},
     sync::atomic::{AtomicU32, Ordering},
 };
 
 use crate::backoff::Backoff;
-use crate::hint::unlikely;
+use crate::relax::RelaxStrategy;
 
+/// Set while an upgradeable reader holds the lock. Blocks other writers and
+/// other upgradeable readers, but still lets plain readers through.
+const UPGRADED: u32 = 1_u32 << 29;
+/// Set while a writer holds the lock (exclusively).
 const WRITE_NUMBER: u32 = 1_u32 << 30;
+/// Bits 0..29 hold the count of live plain readers.
+const READERS_MASK: u32 = UPGRADED - 1;
 
-pub struct RwSpinlock<T> {
+/// A reader-writer spinlock.
+///
+/// `R` picks what the acquisition loops do between failed attempts; it
+/// defaults to the exponential [`Backoff`], but any [`RelaxStrategy`] can be
+/// substituted to match the contention profile of the target environment.
+pub struct RwSpinlock<T, R = Backoff> {
     flag: AtomicU32,
     value: UnsafeCell<T>,
+    _relax: PhantomData<fn() -> R>,
 }
 
-unsafe impl<T: Send> Send for RwSpinlock<T> {}
+unsafe impl<T: Send, R> Send for RwSpinlock<T, R> {}
 
-unsafe impl<T: Send> Sync for RwSpinlock<T> {}
+unsafe impl<T: Send, R> Sync for RwSpinlock<T, R> {}
 
-impl<T> RwSpinlock<T> {
+impl<T> RwSpinlock<T, Backoff> {
+    /// Creates a new unlocked `RwSpinlock` using the default exponential
+    /// [`Backoff`] relax strategy.
+    ///
+    /// Being a `const fn`, this can initialize a `static`, e.g.
+    /// `static TABLE: RwSpinlock<Vec<u32>> = RwSpinlock::new(Vec::new());`,
+    /// without going through [`crate::once::Once`]/`OnceLock`.
+    ///
+    /// Use [`RwSpinlock::with_relax`] to pick a different [`RelaxStrategy`];
+    /// it lives as a distinct inherent method, rather than relying on `R`'s
+    /// default, because that's what lets `R` be inferred here without a type
+    /// annotation, the same trick `HashMap::new` uses for `RandomState`.
     #[inline(always)]
-    pub fn new(value: T) -> Self {
+    pub const fn new(value: T) -> Self {
+        Self::with_relax(value)
+    }
+}
+
+impl<T, R: RelaxStrategy> RwSpinlock<T, R> {
+    /// Creates a new unlocked `RwSpinlock` using a specific [`RelaxStrategy`].
+    #[inline(always)]
+    pub const fn with_relax(value: T) -> Self {
         Self {
             flag: AtomicU32::new(0),
             value: UnsafeCell::new(value),
+            _relax: PhantomData,
         }
     }
 
-    pub fn try_write(&self) -> Option<RwSpinlockGuard<T>> {
+    pub fn try_write(&self) -> Option<RwSpinlockWriteGuard<'_, T, R>> {
         if self.flag.compare_exchange_weak(
             0,
             WRITE_NUMBER,
             Ordering::Acquire,
             Ordering::Relaxed,
         ).is_ok() {
-            return Some(RwSpinlockGuard { parent: self });
+            return Some(RwSpinlockWriteGuard { parent: self });
         }
         None
     }
 
-    pub fn try_read(&self) -> Option<RwSpinlockGuard<T>> {
+    pub fn try_read(&self) -> Option<RwSpinlockReadGuard<'_, T, R>> {
         let pre_value = self.flag.fetch_add(1, Ordering::Relaxed);
         if pre_value < WRITE_NUMBER {
-            return Some(RwSpinlockGuard { parent: self });
+            return Some(RwSpinlockReadGuard { parent: self });
         }
+        // A writer is present: undo our speculative increment instead of
+        // leaving a phantom reader behind, or `upgrade()`'s drain loop
+        // could spin on a reader count that never reaches zero.
+        self.flag.fetch_sub(1, Ordering::Relaxed);
         None
     }
 
-    pub fn write(&self) -> RwSpinlockGuard<T> {
-        let backoff = Backoff::default();
+    pub fn write(&self) -> RwSpinlockWriteGuard<'_, T, R> {
+        let relax = R::default();
         loop {
             // "compare_exchange" performance is better than "swap".
             // The reason for using a weak "compare_exchange" is explained here:
@@ -84,45 +121,121 @@ impl<T> RwSpinlock<T> {
 
             while self.flag.load(Ordering::Relaxed) != 0 {
                 // Waits the lock is unlocked to reduce CPU cache coherence.
-                backoff.spin();
+                relax.relax();
             }
         }
-        RwSpinlockGuard { parent: self }
+        RwSpinlockWriteGuard { parent: self }
     }
 
-    pub fn read(&self) -> RwSpinlockGuard<T> {
-        let backoff = Backoff::default();
+    pub fn read(&self) -> RwSpinlockReadGuard<'_, T, R> {
+        let relax = R::default();
         loop {
             let pre_value = self.flag.fetch_add(1, Ordering::Relaxed);
             if pre_value < WRITE_NUMBER {
                 break;
             }
+            // A writer is present: undo our speculative increment instead of
+            // leaving a phantom reader behind, or `upgrade()`'s drain loop
+            // could spin on a reader count that never reaches zero.
+            self.flag.fetch_sub(1, Ordering::Relaxed);
 
-            while self.flag.load(Ordering::Relaxed) != 0 {
+            while self.flag.load(Ordering::Relaxed) & WRITE_NUMBER != 0 {
+                // Waits for the writer to finish; plain readers may still
+                // join while only an upgradeable reader is held, so we don't
+                // wait for the whole flag to reach zero here.
+                relax.relax();
+            }
+        }
+        RwSpinlockReadGuard { parent: self }
+    }
+
+    pub fn try_upgradeable_read(&self) -> Option<RwSpinlockUpgradeableGuard<'_, T, R>> {
+        let current = self.flag.load(Ordering::Relaxed);
+        if current & (UPGRADED | WRITE_NUMBER) == 0
+            && self.flag.compare_exchange_weak(
+                current,
+                current | UPGRADED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ).is_ok() {
+            return Some(RwSpinlockUpgradeableGuard { parent: self });
+        }
+        None
+    }
+
+    pub fn upgradeable_read(&self) -> RwSpinlockUpgradeableGuard<'_, T, R> {
+        let relax = R::default();
+        loop {
+            let current = self.flag.load(Ordering::Relaxed);
+            if current & (UPGRADED | WRITE_NUMBER) == 0
+                && self.flag.compare_exchange_weak(
+                    current,
+                    current | UPGRADED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ).is_ok() {
+                break;
+            }
+
+            while self.flag.load(Ordering::Relaxed) & (UPGRADED | WRITE_NUMBER) != 0 {
                 // Waits the lock is unlocked to reduce CPU cache coherence.
-                backoff.spin();
+                relax.relax();
             }
         }
-        RwSpinlockGuard { parent: self }
+        RwSpinlockUpgradeableGuard { parent: self }
     }
 }
 
-pub struct RwSpinlockGuard<'a, T> {
-    parent: &'a RwSpinlock<T>,
+/// A guard holding shared (read-only) access to a [`RwSpinlock`]. Only
+/// implements [`Deref`] so that, unlike the unsplit guard this type
+/// replaced, it cannot be used to obtain `&mut T` while other readers are
+/// live.
+pub struct RwSpinlockReadGuard<'a, T, R = Backoff> {
+    parent: &'a RwSpinlock<T, R>,
+}
+
+/// A guard holding exclusive (read-write) access to a [`RwSpinlock`].
+pub struct RwSpinlockWriteGuard<'a, T, R = Backoff> {
+    parent: &'a RwSpinlock<T, R>,
+}
+
+/// Holds an upgradeable read lock: concurrent plain readers are still
+/// allowed in, but no other writer or upgradeable reader can join until
+/// this guard is dropped or [`RwSpinlockUpgradeableGuard::upgrade`] is
+/// called.
+pub struct RwSpinlockUpgradeableGuard<'a, T, R = Backoff> {
+    parent: &'a RwSpinlock<T, R>,
+}
+
+impl<'a, T, R: RelaxStrategy> RwSpinlockUpgradeableGuard<'a, T, R> {
+    /// Atomically promotes this upgradeable read lock to an exclusive write
+    /// lock, without ever releasing the lock to another writer in between.
+    pub fn upgrade(self) -> RwSpinlockWriteGuard<'a, T, R> {
+        let parent = self.parent;
+        parent.flag.fetch_or(WRITE_NUMBER, Ordering::Acquire);
+
+        let relax = R::default();
+        while parent.flag.load(Ordering::Relaxed) & READERS_MASK != 0 {
+            // Waits for the in-flight plain readers to drain.
+            relax.relax();
+        }
+        parent.flag.fetch_and(!UPGRADED, Ordering::Release);
+
+        // The upgrade already performed the bit transition that `Drop` would
+        // otherwise undo, so skip it.
+        core::mem::forget(self);
+        RwSpinlockWriteGuard { parent }
+    }
 }
 
-impl<T> Drop for RwSpinlockGuard<'_, T> {
+impl<T, R> Drop for RwSpinlockUpgradeableGuard<'_, T, R> {
     #[inline(always)]
     fn drop(&mut self) {
-        if unlikely(self.parent.flag.load(Ordering::Relaxed) >= WRITE_NUMBER) {
-            self.parent.flag.store(0, Ordering::Release);
-        } else {
-            self.parent.flag.fetch_sub(1, Ordering::Relaxed);
-        }
+        self.parent.flag.fetch_and(!UPGRADED, Ordering::Release);
     }
 }
 
-impl<T> Deref for RwSpinlockGuard<'_, T> {
+impl<T, R> Deref for RwSpinlockUpgradeableGuard<'_, T, R> {
     type Target = T;
 
     #[inline(always)]
@@ -131,18 +244,119 @@ impl<T> Deref for RwSpinlockGuard<'_, T> {
     }
 }
 
-impl<T> DerefMut for RwSpinlockGuard<'_, T> {
+impl<T, R> Drop for RwSpinlockReadGuard<'_, T, R> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        self.parent.flag.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<T, R> Deref for RwSpinlockReadGuard<'_, T, R> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.parent.value.get() }
+    }
+}
+
+impl<T, R> Drop for RwSpinlockWriteGuard<'_, T, R> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        // Clears only the writer bit: a reader that speculatively bumped the
+        // count while we held the lock still has its own decrement pending.
+        self.parent.flag.fetch_and(!WRITE_NUMBER, Ordering::Release);
+    }
+}
+
+impl<T, R> Deref for RwSpinlockWriteGuard<'_, T, R> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.parent.value.get() }
+    }
+}
+
+impl<T, R> DerefMut for RwSpinlockWriteGuard<'_, T, R> {
     #[inline(always)]
     fn deref_mut(&mut self) -> &mut T {
         unsafe { &mut *self.parent.value.get() }
     }
 }
 
+impl<'a, T, R> RwSpinlockWriteGuard<'a, T, R> {
+    /// Makes a new [`MappedRwSpinlockGuard`] for a component of the locked
+    /// data, so the caller can be handed a narrow view without leaking the
+    /// container type. The original write lock is held until the mapped
+    /// guard itself is dropped.
+    pub fn map<U, F>(mut guard: Self, f: F) -> MappedRwSpinlockGuard<'a, U>
+        where F: FnOnce(&mut T) -> &mut U {
+        let value = f(&mut *guard) as *mut U;
+        let flag = &guard.parent.flag;
+        core::mem::forget(guard);
+        MappedRwSpinlockGuard { flag, value }
+    }
+
+    /// Like [`RwSpinlockWriteGuard::map`], but the projection may fail; on
+    /// failure the original guard is handed back unchanged so the caller
+    /// can keep using it.
+    pub fn filter_map<U, F>(mut guard: Self, f: F) -> Result<MappedRwSpinlockGuard<'a, U>, Self>
+        where F: FnOnce(&mut T) -> Option<&mut U> {
+        match f(&mut *guard) {
+            Some(value) => {
+                let value = value as *mut U;
+                let flag = &guard.parent.flag;
+                core::mem::forget(guard);
+                Ok(MappedRwSpinlockGuard { flag, value })
+            }
+            None => Err(guard),
+        }
+    }
+}
+
+/// A write guard over a projected component `U` of a [`RwSpinlock<T>`]'s
+/// data, created via [`RwSpinlockWriteGuard::map`] or
+/// [`RwSpinlockWriteGuard::filter_map`]. Releases the underlying write lock
+/// on drop, exactly like the guard it was created from.
+pub struct MappedRwSpinlockGuard<'a, U> {
+    flag: &'a AtomicU32,
+    value: *mut U,
+}
+
+unsafe impl<U: Sync> Sync for MappedRwSpinlockGuard<'_, U> {}
+
+unsafe impl<U: Send> Send for MappedRwSpinlockGuard<'_, U> {}
+
+impl<U> Drop for MappedRwSpinlockGuard<'_, U> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        self.flag.fetch_and(!WRITE_NUMBER, Ordering::Release);
+    }
+}
+
+impl<U> Deref for MappedRwSpinlockGuard<'_, U> {
+    type Target = U;
+
+    #[inline(always)]
+    fn deref(&self) -> &U {
+        unsafe { &*self.value }
+    }
+}
+
+impl<U> DerefMut for MappedRwSpinlockGuard<'_, U> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe { &mut *self.value }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
-    
+
     use super::*;
+    use crate::relax::{SpinRelax, YieldRelax};
 
     #[allow(unused_variables)]
     #[test]
@@ -170,13 +384,13 @@ mod tests {
         }
         assert!(m.try_read().is_some());
     }
-    
+
     #[test]
     fn test_rw_arc() {
         let arc = Arc::new(RwSpinlock::new(0));
         let arc2 = arc.clone();
         let (tx, rx) = std::sync::mpsc::channel();
-    
+
         std::thread::spawn(move || {
             let mut lock = arc2.write();
             for _ in 0..10 {
@@ -187,7 +401,7 @@ mod tests {
             }
             tx.send(()).unwrap();
         });
-    
+
         // Readers try to catch the writer in the act
         let mut children = Vec::new();
         for _ in 0..5 {
@@ -197,18 +411,18 @@ mod tests {
                 assert!(*lock >= 0);
             }));
         }
-    
+
         // Wait for children to pass their asserts
         for r in children {
             assert!(r.join().is_ok());
         }
-    
+
         // Wait for writer to finish
         rx.recv().unwrap();
         let lock = arc.read();
         assert_eq!(*lock, 10);
     }
-    
+
     #[test]
     fn test_rw_access_in_unwind() {
         let arc = Arc::new(RwSpinlock::new(1));
@@ -230,7 +444,7 @@ mod tests {
         let lock = arc.read();
         assert_eq!(*lock, 2);
     }
-    
+
     #[test]
     fn test_rwlock_unsized() {
         let rw: &RwSpinlock<[i32;3]> = &RwSpinlock::new([1, 2, 3]);
@@ -248,7 +462,7 @@ mod tests {
     fn test_rwlock_try_write() {
         let lock = RwSpinlock::new(0isize);
         let read_guard = lock.read();
-    
+
         let write_result = lock.try_write();
         match write_result {
             None => (),
@@ -257,14 +471,131 @@ mod tests {
                 "try_write should not succeed while read_guard is in scope"
             ),
         }
-    
+
         drop(read_guard);
     }
-    
+
     #[test]
     fn test_rw_try_read() {
         let m = RwSpinlock::new(0);
         std::mem::forget(m.write());
         assert!(m.try_read().is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_upgradeable_read_blocks_writer_but_not_readers() {
+        let m = RwSpinlock::new(0);
+        let upgradeable = m.upgradeable_read();
+        assert!(m.try_write().is_none());
+        assert!(m.try_upgradeable_read().is_none());
+        let reader = m.try_read();
+        assert!(reader.is_some());
+        drop(reader);
+        drop(upgradeable);
+        assert!(m.try_write().is_some());
+    }
+
+    #[test]
+    fn test_upgradeable_read_upgrade() {
+        let m = RwSpinlock::new(1);
+        let upgradeable = m.upgradeable_read();
+        let mut write_guard = upgradeable.upgrade();
+        *write_guard = 2;
+        drop(write_guard);
+        assert_eq!(*m.read(), 2);
+    }
+
+    #[test]
+    fn test_try_read_does_not_leak_reader_count_on_writer_present() {
+        let m = RwSpinlock::new(0);
+        let w = m.write();
+        // A failed `try_read` must not leave a phantom reader behind, or a
+        // later `upgrade()` would spin forever draining a count that never
+        // reaches zero.
+        assert!(m.try_read().is_none());
+        drop(w);
+        assert!(m.try_write().is_some());
+    }
+
+    #[test]
+    fn test_upgrade_does_not_deadlock_with_racing_readers() {
+        let arc = Arc::new(RwSpinlock::new(0));
+        let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let arc2 = arc.clone();
+        let stop2 = stop.clone();
+        let hammer = std::thread::spawn(move || {
+            while !stop2.load(Ordering::Relaxed) {
+                // Racing `try_read`s land on every side of `upgrade()`'s
+                // `WRITE_NUMBER` CAS; none of them must leave a phantom
+                // reader behind.
+                drop(arc2.try_read());
+            }
+        });
+
+        let upgradeable = arc.upgradeable_read();
+        let mut write_guard = upgradeable.upgrade();
+        *write_guard = 7;
+        drop(write_guard);
+
+        stop.store(true, Ordering::Relaxed);
+        hammer.join().unwrap();
+
+        assert_eq!(*arc.read(), 7);
+    }
+
+    #[test]
+    fn test_rw_with_spin_relax() {
+        let m: RwSpinlock<i32, SpinRelax> = RwSpinlock::with_relax(0);
+        *m.write() = 1;
+        assert_eq!(*m.read(), 1);
+    }
+
+    #[test]
+    fn test_rw_with_yield_relax() {
+        let m: RwSpinlock<i32, YieldRelax> = RwSpinlock::with_relax(0);
+        *m.write() = 1;
+        assert_eq!(*m.read(), 1);
+    }
+
+    #[test]
+    fn test_new_is_const() {
+        static TABLE: RwSpinlock<Vec<u32>> = RwSpinlock::new(Vec::new());
+        TABLE.write().push(1);
+        assert_eq!(&*TABLE.read(), &[1]);
+    }
+
+    struct Pair {
+        first: i32,
+        second: i32,
+    }
+
+    #[test]
+    fn test_guard_map() {
+        let m = RwSpinlock::new(Pair { first: 1, second: 2 });
+        {
+            let mut mapped = RwSpinlockWriteGuard::map(m.write(), |p| &mut p.second);
+            *mapped = 3;
+        }
+        let read = m.read();
+        assert_eq!(read.first, 1);
+        assert_eq!(read.second, 3);
+        drop(read);
+        assert!(m.try_write().is_some());
+    }
+
+    #[test]
+    fn test_guard_filter_map() {
+        let m = RwSpinlock::new(Some(1));
+        let mapped = RwSpinlockWriteGuard::filter_map(m.write(), |v| v.as_mut());
+        assert!(mapped.is_ok());
+        assert_eq!(*mapped.ok().unwrap(), 1);
+        assert!(m.try_write().is_some());
+
+        let m = RwSpinlock::new(None::<i32>);
+        let guard = RwSpinlockWriteGuard::filter_map(m.write(), |v| v.as_mut());
+        assert!(guard.is_err());
+        drop(guard);
+        assert!(m.try_write().is_some());
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright (c) 2024 Trung Tran <tqtrungse@gmail.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    cell::{Cell, UnsafeCell},
+    mem::MaybeUninit,
+    ops::Deref,
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::backoff::Backoff;
+use crate::defer::Defer;
+
+const INCOMPLETE: u32 = 0;
+const RUNNING: u32 = 1;
+const COMPLETE: u32 = 2;
+const PANICKED: u32 = 3;
+
+/// A spin-based equivalent of `std::sync::OnceLock`: runs an initializer
+/// exactly once, even when many threads race to call `call_once`.
+pub struct Once<T> {
+    state: AtomicU32,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send> Send for Once<T> {}
+
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+impl<T> Once<T> {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(INCOMPLETE),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Returns the value if it has already been initialized, without
+    /// blocking.
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) == COMPLETE {
+            return Some(unsafe { (*self.value.get()).assume_init_ref() });
+        }
+        None
+    }
+
+    /// Runs `init` the first time `call_once` is called on this `Once`, and
+    /// returns the value every time, including on subsequent calls.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `init` panicked on a previous call, since the `Once` is
+    /// then permanently poisoned.
+    pub fn call_once(&self, init: impl FnOnce() -> T) -> &T {
+        let backoff = Backoff::default();
+        loop {
+            if self.state.compare_exchange_weak(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ).is_ok() {
+                // Resets the state to `PANICKED` if `init` unwinds, so the
+                // `Once` isn't left stuck in `RUNNING` forever. Disarmed
+                // below once the value has actually been written.
+                let poison_on_unwind = Defer::new(|| {
+                    self.state.store(PANICKED, Ordering::Release);
+                });
+                let value = init();
+                unsafe {
+                    (*self.value.get()).write(value);
+                }
+                std::mem::forget(poison_on_unwind);
+                self.state.store(COMPLETE, Ordering::Release);
+                break;
+            }
+
+            match self.state.load(Ordering::Acquire) {
+                COMPLETE => break,
+                PANICKED => panic!("Once instance has previously been poisoned"),
+                _ => {
+                    while self.state.load(Ordering::Acquire) == RUNNING {
+                        // Waits for the winning thread to finish initializing.
+                        backoff.spin();
+                    }
+                }
+            }
+        }
+        unsafe { (*self.value.get()).assume_init_ref() }
+    }
+}
+
+impl<T> Default for Once<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            unsafe {
+                ptr::drop_in_place((*self.value.get()).as_mut_ptr());
+            }
+        }
+    }
+}
+
+/// A value that is lazily initialized on first [`Deref`], backed by
+/// [`Once`].
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: Cell<Option<F>>,
+}
+
+unsafe impl<T, F: Send> Sync for Lazy<T, F> where Once<T>: Sync {}
+
+impl<T, F> Lazy<T, F> {
+    #[inline(always)]
+    pub const fn new(init: F) -> Self {
+        Self {
+            once: Once::new(),
+            init: Cell::new(Some(init)),
+        }
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.once.call_once(|| match self.init.take() {
+            Some(f) => f(),
+            // Only the thread that wins `Once`'s race ever calls `take`, so
+            // this only happens if `init` panicked on a previous call, in
+            // which case `call_once` itself already panics first.
+            None => unreachable!("Lazy instance has previously been poisoned"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::AtomicUsize, Arc};
+
+    use super::*;
+
+    #[test]
+    fn test_get_before_init() {
+        let once = Once::<i32>::new();
+        assert!(once.get().is_none());
+    }
+
+    #[test]
+    fn test_call_once_runs_init_once() {
+        let calls = AtomicUsize::new(0);
+        let once = Once::new();
+        for _ in 0..10 {
+            let value = once.call_once(|| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                42
+            });
+            assert_eq!(*value, 42);
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(*once.get().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_call_once_concurrent() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let once = Arc::new(Once::new());
+        let mut children = Vec::new();
+        for _ in 0..10 {
+            let calls = calls.clone();
+            let once = once.clone();
+            children.push(std::thread::spawn(move || {
+                *once.call_once(|| {
+                    calls.fetch_add(1, Ordering::Relaxed);
+                    7
+                })
+            }));
+        }
+        for child in children {
+            assert_eq!(child.join().unwrap(), 7);
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_call_once_poisons_on_panic() {
+        let once = Once::<i32>::new();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            once.call_once(|| panic!("boom"));
+        }));
+        assert!(result.is_err());
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            once.call_once(|| 1);
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_lazy_deref() {
+        let calls = AtomicUsize::new(0);
+        let lazy = Lazy::new(|| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            String::from("hello")
+        });
+        assert_eq!(&*lazy, "hello");
+        assert_eq!(&*lazy, "hello");
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright (c) 2024 Trung Tran <tqtrungse@gmail.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::backoff::Backoff;
+
+/// Pluggable strategy for what a spin loop does between failed attempts to
+/// acquire a lock.
+///
+/// `RwSpinlock` is generic over this trait so callers can swap the default
+/// exponential backoff for a strategy that better fits their environment,
+/// e.g. a bare spin loop for `no_std`/interrupt contexts or a yielding
+/// strategy for single-core or oversubscribed systems.
+pub trait RelaxStrategy: Default {
+    /// Gives up the CPU (or just hints it) before the next attempt.
+    fn relax(&self);
+}
+
+/// Spins the CPU in a tight loop via [`core::hint::spin_loop`].
+///
+/// The cheapest strategy; appropriate when no scheduler is available to
+/// yield to, e.g. `no_std`/interrupt contexts.
+#[derive(Default)]
+pub struct SpinRelax;
+
+impl RelaxStrategy for SpinRelax {
+    #[inline(always)]
+    fn relax(&self) {
+        core::hint::spin_loop();
+    }
+}
+
+/// Yields the current thread via [`std::thread::yield_now`].
+///
+/// Preferable on single-core or heavily oversubscribed systems, where
+/// spinning only delays the thread that is actually holding the lock.
+#[derive(Default)]
+pub struct YieldRelax;
+
+impl RelaxStrategy for YieldRelax {
+    #[inline(always)]
+    fn relax(&self) {
+        std::thread::yield_now();
+    }
+}
+
+impl RelaxStrategy for Backoff {
+    #[inline(always)]
+    fn relax(&self) {
+        self.spin();
+    }
+}
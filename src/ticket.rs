@@ -0,0 +1,158 @@
+// Copyright (c) 2024 Trung Tran <tqtrungse@gmail.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::backoff::Backoff;
+
+/// A mutual-exclusion spinlock that serves acquirers in strict FIFO arrival
+/// order.
+///
+/// [`crate::lock::RwSpinlock`]'s `write()` is unfair: under a steady stream
+/// of readers a pending writer's CAS can lose indefinitely, so it can
+/// starve. `TicketSpinlock` trades a little throughput for a starvation
+/// freedom guarantee by having every acquirer draw a ticket and wait its
+/// turn, the same scheme used by ticket locks in the Linux kernel.
+pub struct TicketSpinlock<T> {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for TicketSpinlock<T> {}
+
+unsafe impl<T: Send> Sync for TicketSpinlock<T> {}
+
+impl<T> TicketSpinlock<T> {
+    #[inline(always)]
+    pub fn new(value: T) -> Self {
+        Self {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Draws a ticket and spins until it is called, so callers are served
+    /// in the order they arrived.
+    pub fn lock(&self) -> TicketSpinlockGuard<'_, T> {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+
+        let backoff = Backoff::default();
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            // Waits for our ticket to be called to reduce CPU cache coherence.
+            backoff.spin();
+        }
+        TicketSpinlockGuard { parent: self }
+    }
+}
+
+pub struct TicketSpinlockGuard<'a, T> {
+    parent: &'a TicketSpinlock<T>,
+}
+
+impl<T> Drop for TicketSpinlockGuard<'_, T> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        // Passes the lock to the next ticket in line.
+        self.parent.now_serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl<T> Deref for TicketSpinlockGuard<'_, T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.parent.value.get() }
+    }
+}
+
+impl<T> DerefMut for TicketSpinlockGuard<'_, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.parent.value.get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_lock_unlock() {
+        let m = TicketSpinlock::new(0);
+        {
+            let mut guard = m.lock();
+            *guard = 1;
+        }
+        assert_eq!(*m.lock(), 1);
+    }
+
+    #[test]
+    fn test_ticket_arc() {
+        let arc = Arc::new(TicketSpinlock::new(0));
+        let mut children = Vec::new();
+        for _ in 0..10 {
+            let arc2 = arc.clone();
+            children.push(std::thread::spawn(move || {
+                for _ in 0..100 {
+                    let mut guard = arc2.lock();
+                    *guard += 1;
+                }
+            }));
+        }
+        for child in children {
+            child.join().unwrap();
+        }
+        assert_eq!(*arc.lock(), 1000);
+    }
+
+    #[test]
+    fn test_fifo_order() {
+        let m = Arc::new(TicketSpinlock::new(Vec::new()));
+        let first = m.lock();
+
+        let mut handles = Vec::new();
+        for i in 1..=2 {
+            let m2 = m.clone();
+            handles.push(std::thread::spawn(move || {
+                m2.lock().push(i);
+            }));
+            // Ensures this thread has drawn its ticket before the next one is
+            // spawned, so tickets are drawn in the order 1, 2.
+            while m.next_ticket.load(Ordering::Relaxed) != i + 1 {
+                std::thread::yield_now();
+            }
+        }
+
+        drop(first);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*m.lock(), vec![1, 2]);
+    }
+}